@@ -1,4 +1,4 @@
-use std::{hash::Hasher, time::Duration};
+use std::{collections::VecDeque, hash::Hasher, time::Duration};
 
 use rand::{Rng, RngCore};
 
@@ -24,13 +24,53 @@ pub trait ConnectionIdGenerator: Send + Sync {
     }
 
     /// Returns the length of a CID for connections created by this generator
+    ///
+    /// Generators whose CIDs vary in length, such as [`GreasedConnectionIdGenerator`], return
+    /// their preferred/typical length here and must override [`decode_cid_len`](Self::decode_cid_len)
+    /// so callers can still recover the true length of any individual CID they emit.
     fn cid_len(&self) -> usize;
     /// Returns the lifetime of generated Connection IDs
     ///
     /// Connection IDs will be retired after the returned `Duration`, if any. Assumed to be constant.
     fn cid_lifetime(&self) -> Option<Duration>;
+
+    /// Recovers the length of a CID this generator could have produced, given its first byte
+    ///
+    /// Endpoints should call this rather than assuming [`cid_len`](Self::cid_len) when parsing a
+    /// short header, since some generators (e.g. [`GreasedConnectionIdGenerator`]) vary the CID
+    /// length per call. The default implementation is correct for every fixed-length generator in
+    /// this module. Returns `None` if `first_byte` could not have encoded a valid length.
+    fn decode_cid_len(&self, _first_byte: u8) -> Option<usize> {
+        Some(self.cid_len())
+    }
+
+    /// Returns the maximum number of retired CID sequence numbers the connection layer should
+    /// track while waiting for their `RETIRE_CONNECTION_ID`s
+    ///
+    /// [`cid_lifetime`](Self::cid_lifetime) is the knob that causes CIDs to be retired over time;
+    /// a peer can otherwise force unbounded growth of this bookkeeping by issuing
+    /// `NEW_CONNECTION_ID`s and retiring them faster than the connection can confirm (cf.
+    /// CVE-2024-1410). This value is the generator's recommended cap for that bookkeeping, letting
+    /// the CID generator stay the single place CID resource policy is configured; it is advisory
+    /// here; enforcing it — bounding the set of outstanding retired sequence numbers and raising a
+    /// connection error on overflow — is the responsibility of whatever code drives retirement,
+    /// which is out of scope for this module.
+    fn max_retired_cids(&self) -> u32 {
+        DEFAULT_MAX_RETIRED_CIDS
+    }
 }
 
+/// Default value returned by [`ConnectionIdGenerator::max_retired_cids`]
+///
+/// A flat, conservative bound on outstanding retired sequence numbers, giving an operator enough
+/// slack to absorb a burst of in-flight retirements without allowing a misbehaving peer to grow
+/// the bookkeeping without bound. Not derived from `active_connection_id_limit`, which this
+/// module has no access to; a generator wired up to a [`TransportConfig`] with a larger limit
+/// should override [`max_retired_cids`](ConnectionIdGenerator::max_retired_cids) accordingly.
+///
+/// [`TransportConfig`]: crate::TransportConfig
+const DEFAULT_MAX_RETIRED_CIDS: u32 = 7;
+
 /// The connection ID was not recognized by the [`ConnectionIdGenerator`]
 #[derive(Debug, Copy, Clone)]
 pub struct InvalidCid;
@@ -98,6 +138,9 @@ impl ConnectionIdGenerator for RandomConnectionIdGenerator {
 /// helps prevents Quinn from responding to non-QUIC packets at very low cost.
 pub struct HashedConnectionIdGenerator {
     key: u64,
+    /// Keys retired via [`rotate_to`](Self::rotate_to) or [`add_retired_key`](Self::add_retired_key),
+    /// newest first
+    retired_keys: VecDeque<u64>,
     lifetime: Option<Duration>,
 }
 
@@ -114,6 +157,7 @@ impl HashedConnectionIdGenerator {
     pub fn from_key(key: u64) -> Self {
         Self {
             key,
+            retired_keys: VecDeque::new(),
             lifetime: None,
         }
     }
@@ -123,6 +167,32 @@ impl HashedConnectionIdGenerator {
         self.lifetime = Some(d);
         self
     }
+
+    /// Make `new_key` the key used by [`generate_cid`](ConnectionIdGenerator::generate_cid),
+    /// retiring the current key
+    ///
+    /// CIDs signed under the previous key continue to pass
+    /// [`validate`](ConnectionIdGenerator::validate) until it is dropped via
+    /// [`drop_oldest_retired_key`](Self::drop_oldest_retired_key), allowing zero-downtime key
+    /// rollover: connections and stateless resets signed under the old key keep working during
+    /// the grace period while new connections pick up the new key.
+    pub fn rotate_to(&mut self, new_key: u64) {
+        self.retired_keys.push_front(self.key);
+        self.key = new_key;
+    }
+
+    /// Make `validate` additionally accept CIDs signed under `key`, without changing which key
+    /// [`generate_cid`](ConnectionIdGenerator::generate_cid) uses
+    pub fn add_retired_key(&mut self, key: u64) {
+        self.retired_keys.push_front(key);
+    }
+
+    /// Stop accepting CIDs signed under the oldest retired key
+    ///
+    /// Returns the dropped key, or `None` if there were no retired keys.
+    pub fn drop_oldest_retired_key(&mut self) -> Option<u64> {
+        self.retired_keys.pop_back()
+    }
 }
 
 #[cfg(feature = "ring")]
@@ -145,11 +215,14 @@ impl ConnectionIdGenerator for HashedConnectionIdGenerator {
 
     fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
         let (nonce, signature) = cid.split_at(NONCE_LEN);
-        let mut hasher = rustc_hash::FxHasher::default();
-        hasher.write_u64(self.key);
-        hasher.write(nonce);
-        let expected = hasher.finish().to_le_bytes();
-        match expected[..SIGNATURE_LEN] == signature[..] {
+        let matches_key = |key: u64| {
+            let mut hasher = rustc_hash::FxHasher::default();
+            hasher.write_u64(key);
+            hasher.write(nonce);
+            let expected = hasher.finish().to_le_bytes();
+            expected[..SIGNATURE_LEN] == signature[..]
+        };
+        match matches_key(self.key) || self.retired_keys.iter().any(|&key| matches_key(key)) {
             true => Ok(()),
             false => Err(InvalidCid),
         }
@@ -167,6 +240,581 @@ impl ConnectionIdGenerator for HashedConnectionIdGenerator {
 const NONCE_LEN: usize = 3; // Good for more than 16 million connections
 const SIGNATURE_LEN: usize = 8 - NONCE_LEN; // 8-byte total CID length
 
+/// Generates CIDs whose length varies per call, greasing peers' variable-length CID handling
+///
+/// [`RandomConnectionIdGenerator`] and [`HashedConnectionIdGenerator`] both emit a single fixed
+/// CID length, making a server's CIDs trivially fingerprintable by length alone. This generator
+/// instead picks a per-CID length biased toward `preferred_len` (mostly, to keep header overhead
+/// low) but occasionally anywhere up to `max_len`, the way neqo grease its initial CIDs.
+///
+/// Because the length varies, it is encoded self-delimitingly in the CID's first byte. Endpoints
+/// parsing a short header whose destination CID was issued by this generator must recover the
+/// length via [`decode_cid_len`](ConnectionIdGenerator::decode_cid_len) rather than assuming
+/// [`cid_len`](ConnectionIdGenerator::cid_len), which only reports the preferred length.
+pub struct GreasedConnectionIdGenerator {
+    preferred_len: usize,
+    max_len: usize,
+    lifetime: Option<Duration>,
+}
+
+impl GreasedConnectionIdGenerator {
+    /// Create a generator biased toward `preferred_len`, occasionally emitting up to `max_len`
+    ///
+    /// `preferred_len` must be at least 1 (to leave room for the length-encoding byte) and at
+    /// most `max_len`, which must in turn be at most [`MAX_CID_SIZE`].
+    pub fn new(preferred_len: usize, max_len: usize) -> Self {
+        assert!(preferred_len >= 1);
+        assert!(preferred_len <= max_len);
+        assert!(max_len <= MAX_CID_SIZE);
+        Self {
+            preferred_len,
+            max_len,
+            lifetime: None,
+        }
+    }
+
+    /// Set the lifetime of CIDs created by this generator
+    pub fn set_lifetime(&mut self, d: Duration) -> &mut Self {
+        self.lifetime = Some(d);
+        self
+    }
+
+    /// Chance, out of 100, that a generated CID uses a length drawn uniformly from
+    /// `preferred_len + 1..=max_len` instead of `preferred_len`
+    const GREASE_PERCENT: u8 = 10;
+
+    fn pick_len(&self) -> usize {
+        if self.preferred_len == self.max_len {
+            return self.preferred_len;
+        }
+        let mut rng = rand::thread_rng();
+        if rng.gen_range(0..100) < Self::GREASE_PERCENT {
+            rng.gen_range(self.preferred_len + 1..=self.max_len)
+        } else {
+            self.preferred_len
+        }
+    }
+}
+
+impl ConnectionIdGenerator for GreasedConnectionIdGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let len = self.pick_len();
+        let mut bytes_arr = [0u8; MAX_CID_SIZE];
+        bytes_arr[0] = len as u8;
+        rand::thread_rng().fill_bytes(&mut bytes_arr[1..len]);
+        ConnectionId::new(&bytes_arr[..len])
+    }
+
+    fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
+        match self.decode_cid_len(cid[0]) {
+            Some(len) if len == cid.len() => Ok(()),
+            _ => Err(InvalidCid),
+        }
+    }
+
+    /// Returns the preferred length; actual generated CIDs may be longer, see
+    /// [`decode_cid_len`](ConnectionIdGenerator::decode_cid_len)
+    fn cid_len(&self) -> usize {
+        self.preferred_len
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+
+    /// Recovers the length of a CID this generator could have produced from its self-delimiting
+    /// first byte
+    ///
+    /// Returns `None` if the encoded length falls outside `preferred_len..=max_len`, which can
+    /// only happen for a corrupt or adversarial CID.
+    fn decode_cid_len(&self, first_byte: u8) -> Option<usize> {
+        let len = first_byte as usize;
+        (self.preferred_len..=self.max_len).contains(&len).then_some(len)
+    }
+}
+
+/// Minimum signature length accepted by [`AuthenticatedConnectionIdGenerator`]
+///
+/// Below this, truncating an HMAC tag leaves too little margin against forgery to be worth the
+/// cost of computing it over a non-cryptographic hash.
+const MIN_AUTHENTICATED_SIGNATURE_LEN: usize = 8;
+
+/// Nonce length used by [`AuthenticatedConnectionIdGenerator`]
+const AUTHENTICATED_NONCE_LEN: usize = 4; // Good for more than 4 billion connections
+
+/// Generates CIDs whose signature is a keyed cryptographic MAC, making
+/// [`validate`](ConnectionIdGenerator::validate) a real security boundary
+///
+/// Unlike [`HashedConnectionIdGenerator`], which signs with a non-cryptographic [`FxHasher`] and
+/// can therefore still be spoofed, this generator signs the nonce with HMAC-SHA256 truncated to
+/// the configured signature length. An attacker without the key cannot forge a CID that passes
+/// `validate`, letting a server drop spoofed short-header packets cheaply before any connection
+/// lookup.
+///
+/// [`FxHasher`]: rustc_hash::FxHasher
+#[cfg(feature = "ring")]
+pub struct AuthenticatedConnectionIdGenerator {
+    key: ring::hmac::Key,
+    signature_len: usize,
+    lifetime: Option<Duration>,
+}
+
+#[cfg(feature = "ring")]
+impl AuthenticatedConnectionIdGenerator {
+    /// Create a generator with a random key and the given total CID length
+    ///
+    /// `cid_len` must be greater than [`AUTHENTICATED_NONCE_LEN`], and the resulting signature
+    /// (`cid_len - AUTHENTICATED_NONCE_LEN`) must be at least
+    /// [`MIN_AUTHENTICATED_SIGNATURE_LEN`] bytes.
+    pub fn new(cid_len: usize) -> Self {
+        let mut key_bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key_bytes);
+        Self::from_key(&key_bytes, cid_len)
+    }
+
+    /// Create a generator with a specific key and the given total CID length
+    ///
+    /// Allows [`validate`](ConnectionIdGenerator::validate) to recognize a consistent set of
+    /// connection IDs across restarts.
+    pub fn from_key(key: &[u8], cid_len: usize) -> Self {
+        assert!(cid_len <= MAX_CID_SIZE);
+        let signature_len = cid_len
+            .checked_sub(AUTHENTICATED_NONCE_LEN)
+            .expect("cid_len must be greater than the nonce length");
+        assert!(
+            signature_len >= MIN_AUTHENTICATED_SIGNATURE_LEN,
+            "cid_len must leave at least {MIN_AUTHENTICATED_SIGNATURE_LEN} bytes for the signature"
+        );
+        Self {
+            key: ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key),
+            signature_len,
+            lifetime: None,
+        }
+    }
+
+    /// Set the lifetime of CIDs created by this generator
+    pub fn set_lifetime(&mut self, d: Duration) -> &mut Self {
+        self.lifetime = Some(d);
+        self
+    }
+}
+
+#[cfg(feature = "ring")]
+impl ConnectionIdGenerator for AuthenticatedConnectionIdGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut bytes_arr = [0; MAX_CID_SIZE];
+        rand::thread_rng().fill_bytes(&mut bytes_arr[..AUTHENTICATED_NONCE_LEN]);
+        let tag = ring::hmac::sign(&self.key, &bytes_arr[..AUTHENTICATED_NONCE_LEN]);
+        let cid_len = self.cid_len();
+        bytes_arr[AUTHENTICATED_NONCE_LEN..cid_len]
+            .copy_from_slice(&tag.as_ref()[..self.signature_len]);
+        ConnectionId::new(&bytes_arr[..cid_len])
+    }
+
+    fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
+        if cid.len() != self.cid_len() {
+            return Err(InvalidCid);
+        }
+        let (nonce, signature) = cid.split_at(AUTHENTICATED_NONCE_LEN);
+        let tag = ring::hmac::sign(&self.key, nonce);
+        let expected = &tag.as_ref()[..self.signature_len];
+        match ring::constant_time::verify_slices_are_equal(expected, signature) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(InvalidCid),
+        }
+    }
+
+    fn cid_len(&self) -> usize {
+        AUTHENTICATED_NONCE_LEN + self.signature_len
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+}
+
+/// How the server-ID field embedded by a [`RoutableConnectionIdGenerator`] is protected
+pub enum RoutingMode {
+    /// The server-ID field is stored in the clear
+    ///
+    /// A stateless load balancer can read the routing target directly out of the destination
+    /// CID without sharing any key material with the servers.
+    Plaintext,
+    /// The server-ID and nonce fields are encrypted under a shared AES-128 key
+    ///
+    /// Only supports the common case where `server_id.len() + ROUTABLE_NONCE_LEN == 16`, so the block can
+    /// be encrypted in a single AES-128 pass. Observers without the key cannot correlate CIDs
+    /// belonging to the same server; a load balancer holding the key can still recover the
+    /// server ID via [`RoutableConnectionIdGenerator::decode_server_id`].
+    Encrypted {
+        /// The shared AES-128 key, known to every load balancer that needs to route by CID
+        key: [u8; 16],
+    },
+}
+
+/// Number of nonce bytes following the server-ID field
+const ROUTABLE_NONCE_LEN: usize = 4;
+/// Mask isolating the config rotation codepoint from the first CID byte
+const CONFIG_ROTATION_MASK: u8 = 0b1100_0000;
+/// Bit offset of the config rotation codepoint within the first CID byte
+const CONFIG_ROTATION_SHIFT: u8 = 6;
+
+/// Embeds an operator-assigned server ID in generated CIDs, QUIC-LB style
+///
+/// Lets a stateless L4 load balancer route every short-header packet to the quinn instance that
+/// owns the connection purely by inspecting the destination CID, without keeping per-connection
+/// state on the load balancer. The first byte carries a 2-bit config rotation codepoint
+/// identifying which server-ID/key configuration produced the CID, so operators can rotate
+/// configurations. A generator only accepts its own codepoint:
+/// [`validate`](ConnectionIdGenerator::validate) and
+/// [`decode_server_id`](Self::decode_server_id) reject CIDs carrying any other. The
+/// remainder of the CID holds the server-ID field, chosen by the operator, followed by random
+/// nonce bytes padding out to `cid_len`; see [`RoutingMode`] for whether that block is in the
+/// clear or AES-128 encrypted.
+pub struct RoutableConnectionIdGenerator {
+    config_id: u8,
+    server_id: Vec<u8>,
+    mode: RoutingMode,
+    lifetime: Option<Duration>,
+}
+
+impl RoutableConnectionIdGenerator {
+    /// Create a generator that stores `server_id` in the clear
+    ///
+    /// `config_id` must fit in 2 bits (i.e. be less than 4), and
+    /// `1 + server_id.len() + ROUTABLE_NONCE_LEN` must be at most [`MAX_CID_SIZE`].
+    pub fn new_plaintext(config_id: u8, server_id: Vec<u8>) -> Self {
+        assert!(config_id < 4, "config_id must fit in 2 bits");
+        assert!(
+            1 + server_id.len() + ROUTABLE_NONCE_LEN <= MAX_CID_SIZE,
+            "server_id is too long to fit in a CID"
+        );
+        Self {
+            config_id,
+            server_id,
+            mode: RoutingMode::Plaintext,
+            lifetime: None,
+        }
+    }
+
+    /// Create a generator that encrypts `server_id` and the nonce under `key`
+    ///
+    /// `config_id` must fit in 2 bits, and `server_id.len() + ROUTABLE_NONCE_LEN` must equal 16 so the
+    /// block can be encrypted in a single AES-128 pass.
+    pub fn new_encrypted(config_id: u8, server_id: Vec<u8>, key: [u8; 16]) -> Self {
+        assert!(config_id < 4, "config_id must fit in 2 bits");
+        assert_eq!(
+            server_id.len() + ROUTABLE_NONCE_LEN,
+            16,
+            "server_id + nonce must fill a single AES-128 block"
+        );
+        Self {
+            config_id,
+            server_id,
+            mode: RoutingMode::Encrypted { key },
+            lifetime: None,
+        }
+    }
+
+    /// Set the lifetime of CIDs created by this generator
+    pub fn set_lifetime(&mut self, d: Duration) -> &mut Self {
+        self.lifetime = Some(d);
+        self
+    }
+
+    /// Recover the server ID embedded in `cid`, if it was produced under the active config
+    ///
+    /// Returns `None` if `cid` doesn't have this generator's length, or its config rotation bits
+    /// don't match the active config. A separate load balancer process can construct a decoder
+    /// with just the server-ID width and (for [`RoutingMode::Encrypted`]) the shared key to
+    /// extract the routing target without needing to generate CIDs itself.
+    pub fn decode_server_id(&self, cid: &ConnectionId) -> Option<Vec<u8>> {
+        if cid.len() != self.cid_len() {
+            return None;
+        }
+        if (cid[0] & CONFIG_ROTATION_MASK) >> CONFIG_ROTATION_SHIFT != self.config_id {
+            return None;
+        }
+        let server_id_len = self.server_id.len();
+        match &self.mode {
+            RoutingMode::Plaintext => Some(cid[1..1 + server_id_len].to_vec()),
+            RoutingMode::Encrypted { key } => {
+                let mut block = [0u8; 16];
+                block.copy_from_slice(&cid[1..17]);
+                aes128_decrypt_block(key, &mut block);
+                Some(block[..server_id_len].to_vec())
+            }
+        }
+    }
+}
+
+impl ConnectionIdGenerator for RoutableConnectionIdGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut bytes_arr = [0; MAX_CID_SIZE];
+        bytes_arr[0] = self.config_id << CONFIG_ROTATION_SHIFT;
+
+        match &self.mode {
+            RoutingMode::Plaintext => {
+                let server_id_end = 1 + self.server_id.len();
+                bytes_arr[1..server_id_end].copy_from_slice(&self.server_id);
+                rand::thread_rng().fill_bytes(&mut bytes_arr[server_id_end..self.cid_len()]);
+            }
+            RoutingMode::Encrypted { key } => {
+                let mut block = [0u8; 16];
+                block[..self.server_id.len()].copy_from_slice(&self.server_id);
+                rand::thread_rng().fill_bytes(&mut block[self.server_id.len()..]);
+                aes128_encrypt_block(key, &mut block);
+                bytes_arr[1..17].copy_from_slice(&block);
+            }
+        }
+
+        ConnectionId::new(&bytes_arr[..self.cid_len()])
+    }
+
+    fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
+        if cid.len() != self.cid_len() {
+            return Err(InvalidCid);
+        }
+        match (cid[0] & CONFIG_ROTATION_MASK) >> CONFIG_ROTATION_SHIFT == self.config_id {
+            true => Ok(()),
+            false => Err(InvalidCid),
+        }
+    }
+
+    fn cid_len(&self) -> usize {
+        match self.mode {
+            RoutingMode::Plaintext => 1 + self.server_id.len() + ROUTABLE_NONCE_LEN,
+            RoutingMode::Encrypted { .. } => 1 + 16,
+        }
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+}
+
+/// Encrypt a single 16-byte block in place under `key`, using AES-128 in a single pass
+fn aes128_encrypt_block(key: &[u8; 16], block: &mut [u8; 16]) {
+    aes128::encrypt_block(key, block);
+}
+
+/// Decrypt a single 16-byte block in place under `key`, the inverse of [`aes128_encrypt_block`]
+fn aes128_decrypt_block(key: &[u8; 16], block: &mut [u8; 16]) {
+    aes128::decrypt_block(key, block);
+}
+
+/// A minimal, self-contained AES-128 block cipher
+///
+/// [`RoutableConnectionIdGenerator`]'s `Encrypted` mode only ever needs single-block ECB
+/// encrypt/decrypt of exactly 16 bytes, so rather than taking on an external crate dependency for
+/// it, this module implements the textbook algorithm directly. It is not constant-time and is
+/// scoped deliberately narrowly to this one use; reach for a hardened crate instead if this cipher
+/// needs to protect anything beyond obfuscating a load-balancer routing tag.
+mod aes128 {
+    const ROUNDS: usize = 10;
+
+    #[rustfmt::skip]
+    const SBOX: [u8; 256] = [
+        0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+        0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+        0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+        0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+        0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+        0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+        0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+        0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+        0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+        0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+        0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+        0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+        0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+        0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+        0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+        0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+    ];
+
+    const RCON: [u8; ROUNDS] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+    fn inv_sbox() -> [u8; 256] {
+        let mut inv = [0u8; 256];
+        for (i, &s) in SBOX.iter().enumerate() {
+            inv[s as usize] = i as u8;
+        }
+        inv
+    }
+
+    /// GF(2^8) multiplication under the AES reduction polynomial
+    fn mul(mut a: u8, mut b: u8) -> u8 {
+        let mut p = 0u8;
+        for _ in 0..8 {
+            if b & 1 != 0 {
+                p ^= a;
+            }
+            let hi = a & 0x80;
+            a <<= 1;
+            if hi != 0 {
+                a ^= 0x1b;
+            }
+            b >>= 1;
+        }
+        p
+    }
+
+    /// Expand a 16-byte key into 11 round keys (44 32-bit words) per the Rijndael key schedule
+    fn key_expansion(key: &[u8; 16]) -> [[u8; 4]; 44] {
+        let mut w = [[0u8; 4]; 44];
+        for i in 0..4 {
+            w[i].copy_from_slice(&key[4 * i..4 * i + 4]);
+        }
+        for i in 4..44 {
+            let mut temp = w[i - 1];
+            if i % 4 == 0 {
+                temp = [temp[1], temp[2], temp[3], temp[0]];
+                for b in &mut temp {
+                    *b = SBOX[*b as usize];
+                }
+                temp[0] ^= RCON[i / 4 - 1];
+            }
+            for j in 0..4 {
+                w[i][j] = w[i - 4][j] ^ temp[j];
+            }
+        }
+        w
+    }
+
+    fn add_round_key(state: &mut [[u8; 4]; 4], w: &[[u8; 4]; 44], round: usize) {
+        for c in 0..4 {
+            for r in 0..4 {
+                state[r][c] ^= w[round * 4 + c][r];
+            }
+        }
+    }
+
+    fn sub_bytes(state: &mut [[u8; 4]; 4], sbox: &[u8; 256]) {
+        for row in state.iter_mut() {
+            for b in row.iter_mut() {
+                *b = sbox[*b as usize];
+            }
+        }
+    }
+
+    fn shift_rows(state: &mut [[u8; 4]; 4]) {
+        for (r, row) in state.iter_mut().enumerate() {
+            row.rotate_left(r);
+        }
+    }
+
+    fn inv_shift_rows(state: &mut [[u8; 4]; 4]) {
+        for (r, row) in state.iter_mut().enumerate() {
+            row.rotate_right(r);
+        }
+    }
+
+    fn mix_columns(state: &mut [[u8; 4]; 4]) {
+        for c in 0..4 {
+            let col = [state[0][c], state[1][c], state[2][c], state[3][c]];
+            state[0][c] = mul(col[0], 2) ^ mul(col[1], 3) ^ col[2] ^ col[3];
+            state[1][c] = col[0] ^ mul(col[1], 2) ^ mul(col[2], 3) ^ col[3];
+            state[2][c] = col[0] ^ col[1] ^ mul(col[2], 2) ^ mul(col[3], 3);
+            state[3][c] = mul(col[0], 3) ^ col[1] ^ col[2] ^ mul(col[3], 2);
+        }
+    }
+
+    fn inv_mix_columns(state: &mut [[u8; 4]; 4]) {
+        for c in 0..4 {
+            let col = [state[0][c], state[1][c], state[2][c], state[3][c]];
+            state[0][c] =
+                mul(col[0], 14) ^ mul(col[1], 11) ^ mul(col[2], 13) ^ mul(col[3], 9);
+            state[1][c] =
+                mul(col[0], 9) ^ mul(col[1], 14) ^ mul(col[2], 11) ^ mul(col[3], 13);
+            state[2][c] =
+                mul(col[0], 13) ^ mul(col[1], 9) ^ mul(col[2], 14) ^ mul(col[3], 11);
+            state[3][c] =
+                mul(col[0], 11) ^ mul(col[1], 13) ^ mul(col[2], 9) ^ mul(col[3], 14);
+        }
+    }
+
+    fn bytes_to_state(block: &[u8; 16]) -> [[u8; 4]; 4] {
+        let mut state = [[0u8; 4]; 4];
+        for i in 0..16 {
+            state[i % 4][i / 4] = block[i];
+        }
+        state
+    }
+
+    fn state_to_bytes(state: &[[u8; 4]; 4], block: &mut [u8; 16]) {
+        for i in 0..16 {
+            block[i] = state[i % 4][i / 4];
+        }
+    }
+
+    pub(super) fn encrypt_block(key: &[u8; 16], block: &mut [u8; 16]) {
+        let w = key_expansion(key);
+        let mut state = bytes_to_state(block);
+        add_round_key(&mut state, &w, 0);
+        for round in 1..ROUNDS {
+            sub_bytes(&mut state, &SBOX);
+            shift_rows(&mut state);
+            mix_columns(&mut state);
+            add_round_key(&mut state, &w, round);
+        }
+        sub_bytes(&mut state, &SBOX);
+        shift_rows(&mut state);
+        add_round_key(&mut state, &w, ROUNDS);
+        state_to_bytes(&state, block);
+    }
+
+    pub(super) fn decrypt_block(key: &[u8; 16], block: &mut [u8; 16]) {
+        let w = key_expansion(key);
+        let inv_sbox = inv_sbox();
+        let mut state = bytes_to_state(block);
+        add_round_key(&mut state, &w, ROUNDS);
+        for round in (1..ROUNDS).rev() {
+            inv_shift_rows(&mut state);
+            sub_bytes(&mut state, &inv_sbox);
+            add_round_key(&mut state, &w, round);
+            inv_mix_columns(&mut state);
+        }
+        inv_shift_rows(&mut state);
+        sub_bytes(&mut state, &inv_sbox);
+        add_round_key(&mut state, &w, 0);
+        state_to_bytes(&state, block);
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn matches_fips_197_test_vector() {
+            let key = [
+                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+                0x0e, 0x0f,
+            ];
+            let mut block = [
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+                0xee, 0xff,
+            ];
+            let expected = [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a,
+            ];
+
+            encrypt_block(&key, &mut block);
+            assert_eq!(block, expected);
+
+            decrypt_block(&key, &mut block);
+            assert_eq!(
+                block,
+                [
+                    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc,
+                    0xdd, 0xee, 0xff,
+                ]
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,4 +826,79 @@ mod tests {
         let cid = generator.generate_cid();
         generator.validate(&cid).unwrap();
     }
+
+    #[test]
+    fn rotate_key_preserves_old_cid_validation() {
+        let mut generator = HashedConnectionIdGenerator::from_key(1);
+        let old_cid = generator.generate_cid();
+
+        generator.rotate_to(2);
+        let new_cid = generator.generate_cid();
+        generator.validate(&old_cid).unwrap();
+        generator.validate(&new_cid).unwrap();
+
+        generator.drop_oldest_retired_key();
+        assert!(generator.validate(&old_cid).is_err());
+        generator.validate(&new_cid).unwrap();
+    }
+
+    #[test]
+    fn greased_cid_lengths_stay_in_range_and_validate() {
+        let mut generator = GreasedConnectionIdGenerator::new(8, MAX_CID_SIZE);
+        for _ in 0..100 {
+            let cid = generator.generate_cid();
+            assert!(cid.len() >= 8 && cid.len() <= MAX_CID_SIZE);
+            assert_eq!(generator.decode_cid_len(cid[0]), Some(cid.len()));
+            generator.validate(&cid).unwrap();
+        }
+    }
+
+    #[test]
+    fn default_max_retired_cids_is_a_small_flat_bound() {
+        let generator = RandomConnectionIdGenerator::default();
+        assert_eq!(generator.max_retired_cids(), 7);
+    }
+
+    #[test]
+    #[cfg(feature = "ring")]
+    fn validate_authenticated_cid() {
+        let mut generator = AuthenticatedConnectionIdGenerator::new(16);
+        let cid = generator.generate_cid();
+        generator.validate(&cid).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "ring")]
+    fn reject_forged_authenticated_cid() {
+        let mut generator = AuthenticatedConnectionIdGenerator::new(16);
+        let other = AuthenticatedConnectionIdGenerator::new(16);
+        let cid = generator.generate_cid();
+        assert!(other.validate(&cid).is_err());
+    }
+
+    #[test]
+    fn routable_plaintext_roundtrip() {
+        let mut generator = RoutableConnectionIdGenerator::new_plaintext(1, vec![0x42, 0x17]);
+        let cid = generator.generate_cid();
+        generator.validate(&cid).unwrap();
+        assert_eq!(generator.decode_server_id(&cid), Some(vec![0x42, 0x17]));
+    }
+
+    #[test]
+    fn routable_encrypted_roundtrip() {
+        let server_id = vec![0x42, 0x17, 0x99, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let mut generator =
+            RoutableConnectionIdGenerator::new_encrypted(2, server_id.clone(), [7u8; 16]);
+        let cid = generator.generate_cid();
+        generator.validate(&cid).unwrap();
+        assert_eq!(generator.decode_server_id(&cid), Some(server_id));
+    }
+
+    #[test]
+    fn routable_rejects_wrong_config() {
+        let mut generator = RoutableConnectionIdGenerator::new_plaintext(1, vec![0x42, 0x17]);
+        let cid = generator.generate_cid();
+        let other = RoutableConnectionIdGenerator::new_plaintext(2, vec![0x42, 0x17]);
+        assert!(other.validate(&cid).is_err());
+    }
 }